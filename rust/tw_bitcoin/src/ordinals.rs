@@ -1,8 +1,24 @@
-use crate::{Error, Recipient, Result, TaprootProgram, TaprootScript};
-use bitcoin::script::{PushBytesBuf, ScriptBuf};
+use crate::{Error, InscriptionId, Recipient, Result, TaprootProgram, TaprootScript};
+use bitcoin::hashes::Hash;
+use bitcoin::script::{Builder, Instruction, PushBytesBuf, ScriptBuf};
 use bitcoin::secp256k1::XOnlyPublicKey;
-use bitcoin::taproot::{TaprootBuilder, TaprootSpendInfo};
-use bitcoin::{PublicKey, Script};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{PublicKey, Script, Txid, Witness};
+
+/// The maximum number of bytes that can be pushed onto the stack in a single
+/// script element (`MAX_SCRIPT_ELEMENT_SIZE` in Bitcoin Core). Inscription
+/// bodies larger than this must be split into several sequential pushes.
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// Tag bytes for the optional envelope fields, as defined by the
+/// [Ordinals Inscription spec](https://docs.ordinals.com/inscriptions.html).
+/// Listed here in the canonical ascending order in which they must appear
+/// in the envelope, before the body separator (tag `0`).
+const TAG_POINTER: u8 = 2;
+const TAG_PARENT: u8 = 3;
+const TAG_METADATA: u8 = 5;
+const TAG_CONTENT_ENCODING: u8 = 9;
+const TAG_DELEGATE: u8 = 11;
 
 #[derive(Debug, Clone)]
 pub struct OrdinalsInscription {
@@ -17,8 +33,230 @@ impl OrdinalsInscription {
         data: &[u8],
         recipient: Recipient<PublicKey>,
     ) -> Result<OrdinalsInscription> {
+        Self::builder(mime, data, recipient).build()
+    }
+    /// Starts building an Ordinals Inscription with optional envelope tags,
+    /// such as a parent/delegate inscription or on-chain metadata. See
+    /// [`OrdinalsInscriptionBuilder`].
+    pub fn builder(
+        mime: &[u8],
+        data: &[u8],
+        recipient: Recipient<PublicKey>,
+    ) -> OrdinalsInscriptionBuilder {
+        OrdinalsInscriptionBuilder {
+            mime: mime.to_vec(),
+            data: data.to_vec(),
+            recipient,
+            tags: EnvelopeTags::default(),
+        }
+    }
+    pub fn taproot_program(&self) -> &Script {
+        self.envelope.script.as_script()
+    }
+    pub fn spend_info(&self) -> &TaprootSpendInfo {
+        &self.envelope.spend_info
+    }
+    pub fn recipient(&self) -> &Recipient<TaprootScript> {
+        &self.recipient
+    }
+    /// Decodes an Ordinals Inscription envelope out of a reveal `script`.
+    /// This is the inverse of [`create_envelope`]: it locates the
+    /// `OP_FALSE OP_IF "ord" ... OP_ENDIF` envelope, reassembles the
+    /// (possibly chunked) body and returns the MIME type together with any
+    /// recognized tag fields. Any leading spending condition before the
+    /// envelope (eg. `create_envelope`'s `<internal_key> OP_CHECKSIG`) is
+    /// skipped, unknown tags are skipped, and anything after the first
+    /// `OP_ENDIF` is ignored.
+    pub fn from_script(script: &Script) -> Result<DecodedInscription> {
+        use bitcoin::opcodes::all::*;
+
+        fn expect_op<E>(
+            instr: Option<std::result::Result<Instruction, E>>,
+            op: bitcoin::opcodes::All,
+        ) -> Result<()> {
+            match instr {
+                Some(Ok(Instruction::Op(found))) if found == op => Ok(()),
+                _ => Err(Error::Todo),
+            }
+        }
+        fn expect_push<E>(instr: Option<std::result::Result<Instruction, E>>) -> Result<Vec<u8>> {
+            match instr {
+                Some(Ok(Instruction::PushBytes(bytes))) => Ok(bytes.as_bytes().to_vec()),
+                _ => Err(Error::Todo),
+            }
+        }
+
+        let mut instructions = script.instructions().peekable();
+
+        // Skip past any leading spending condition before the envelope (eg.
+        // the `<internal_key> OP_CHECKSIG` check `create_envelope` prefixes
+        // the leaf with) until the `OP_FALSE` that opens the envelope itself.
+        loop {
+            match instructions.peek() {
+                Some(Ok(Instruction::PushBytes(bytes))) if bytes.as_bytes().is_empty() => break,
+                Some(Ok(_)) => {
+                    instructions.next();
+                },
+                _ => return Err(Error::Todo),
+            }
+        }
+
+        // `OP_FALSE` (0x00) is itself a zero-length data push, not an opcode
+        // instruction, so it must be matched as an empty `PushBytes`.
+        if !expect_push(instructions.next())?.is_empty() {
+            return Err(Error::Todo);
+        }
+        expect_op(instructions.next(), OP_IF)?;
+
+        if expect_push(instructions.next())? != b"ord" {
+            return Err(Error::Todo);
+        }
+
+        // Content-type tag marker; must be tag `1`, the same one-byte
+        // separator `create_envelope` always emits before the MIME push.
+        if expect_push(instructions.next())? != [1] {
+            return Err(Error::Todo);
+        }
+        let mime = expect_push(instructions.next())?;
+
+        let mut content_encoding = None;
+        let mut metadata: Option<Vec<u8>> = None;
+        let mut pointer = None;
+        let mut parent = None;
+        let mut delegate = None;
+
+        // Walk the remaining tag/value pairs until the body separator, an
+        // empty push representing tag `0`.
+        loop {
+            let tag = expect_push(instructions.next())?;
+            if tag.is_empty() {
+                break;
+            }
+            let value = expect_push(instructions.next())?;
+
+            match tag.as_slice() {
+                [TAG_POINTER] => pointer = Some(decode_tag_u64(&value)?),
+                [TAG_PARENT] => parent = Some(decode_inscription_id(&value)?),
+                [TAG_METADATA] => {
+                    metadata.get_or_insert_with(Vec::new).extend_from_slice(&value)
+                },
+                [TAG_CONTENT_ENCODING] => content_encoding = Some(value),
+                [TAG_DELEGATE] => delegate = Some(decode_inscription_id(&value)?),
+                // Unrecognized tag; skip its value and move on.
+                _ => {},
+            }
+        }
+
+        // Reassemble the (possibly chunked) body, stopping at the envelope's
+        // closing `OP_ENDIF`; any trailing script is ignored.
+        let mut body = Vec::new();
+        loop {
+            match instructions.next() {
+                Some(Ok(Instruction::PushBytes(bytes))) => body.extend_from_slice(bytes.as_bytes()),
+                Some(Ok(Instruction::Op(op))) if op == OP_ENDIF => break,
+                _ => return Err(Error::Todo),
+            }
+        }
+
+        Ok(DecodedInscription {
+            mime,
+            body,
+            content_encoding,
+            metadata,
+            pointer,
+            parent,
+            delegate,
+        })
+    }
+    /// Convenience wrapper around [`OrdinalsInscription::from_script`] that
+    /// pulls the tapscript (the second-to-last witness item, or the
+    /// third-to-last when the witness ends in an annex) out of a reveal
+    /// transaction's `witness` and decodes it.
+    pub fn from_witness(witness: &Witness) -> Result<DecodedInscription> {
+        let script = witness.tapscript().ok_or(Error::Todo)?;
+        Self::from_script(script)
+    }
+    /// Computes the single-leaf Taproot control block needed to spend this
+    /// Inscription's envelope via the script path:
+    /// `(0xc0 | parity_byte) || internal_key_x_only || merkle_path`. The
+    /// control block is verified against the output key before being
+    /// returned, so a caller can trust it will satisfy the verifier.
+    pub fn control_block(&self) -> Result<ControlBlock> {
+        control_block_for(&self.envelope)
+    }
+    /// Assembles the reveal-stage, script-path spend witness for this
+    /// Inscription: `[<signature>, <tapscript>, <control_block>]`. The
+    /// envelope's leaf script spends via `<internal_key> OP_CHECKSIG`, so
+    /// `signature` must be a valid Schnorr signature from that key over the
+    /// reveal transaction (the sole stack input the script consumes).
+    pub fn reveal_witness(&self, signature: &[u8]) -> Result<Witness> {
+        reveal_witness_for(&self.envelope, signature)
+    }
+}
+
+/// The decoded contents of an Ordinals Inscription envelope, as returned by
+/// [`OrdinalsInscription::from_script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInscription {
+    pub mime: Vec<u8>,
+    pub body: Vec<u8>,
+    pub content_encoding: Option<Vec<u8>>,
+    pub metadata: Option<Vec<u8>>,
+    pub pointer: Option<u64>,
+    pub parent: Option<InscriptionId>,
+    pub delegate: Option<InscriptionId>,
+}
+
+/// Builds an [`OrdinalsInscription`] with optional envelope tags. Use
+/// [`OrdinalsInscription::builder`] to create one.
+#[derive(Debug, Clone)]
+pub struct OrdinalsInscriptionBuilder {
+    mime: Vec<u8>,
+    data: Vec<u8>,
+    recipient: Recipient<PublicKey>,
+    tags: EnvelopeTags,
+}
+
+impl OrdinalsInscriptionBuilder {
+    /// Sets the content-encoding tag (eg. `br` or `gzip`), describing how the
+    /// body is encoded.
+    pub fn content_encoding(mut self, content_encoding: &[u8]) -> Self {
+        self.tags.content_encoding = Some(content_encoding.to_vec());
+        self
+    }
+    /// Sets the metadata tag. The value is expected to be CBOR-encoded and is
+    /// split across 520-byte chunks, each re-emitting the metadata tag, the
+    /// same way the body is chunked.
+    pub fn metadata(mut self, metadata: &[u8]) -> Self {
+        self.tags.metadata = Some(metadata.to_vec());
+        self
+    }
+    /// Sets the pointer tag, redirecting the inscription to a sat other than
+    /// the first of its input.
+    pub fn pointer(mut self, pointer: u64) -> Self {
+        self.tags.pointer = Some(pointer);
+        self
+    }
+    /// Sets the parent tag, making this inscription a child of `parent`.
+    pub fn parent(mut self, parent: InscriptionId) -> Self {
+        self.tags.parent = Some(parent);
+        self
+    }
+    /// Sets the delegate tag, making this inscription inherit the content of
+    /// `delegate`.
+    pub fn delegate(mut self, delegate: InscriptionId) -> Self {
+        self.tags.delegate = Some(delegate);
+        self
+    }
+    /// Builds the Ordinals Inscription ("commit stage").
+    pub fn build(self) -> Result<OrdinalsInscription> {
         // Create the envelope, containing the inscription content.
-        let envelope = create_envelope(mime, data, recipient.public_key())?;
+        let envelope = create_envelope(
+            &self.mime,
+            &self.data,
+            self.recipient.public_key(),
+            &self.tags,
+        )?;
 
         // Compute the merkle root of the inscription.
         let merkle_root = envelope
@@ -28,18 +266,117 @@ impl OrdinalsInscription {
 
         Ok(OrdinalsInscription {
             envelope,
-            recipient: Recipient::<TaprootScript>::from_pubkey_recipient(recipient, merkle_root),
+            recipient: Recipient::<TaprootScript>::from_pubkey_recipient(
+                self.recipient,
+                merkle_root,
+            ),
         })
     }
-    pub fn taproot_program(&self) -> &Script {
-        self.envelope.script.as_script()
+}
+
+/// The optional envelope tags supported on top of the protocol id, content
+/// type and body. See [`OrdinalsInscriptionBuilder`].
+#[derive(Debug, Clone, Default)]
+struct EnvelopeTags {
+    content_encoding: Option<Vec<u8>>,
+    metadata: Option<Vec<u8>>,
+    pointer: Option<u64>,
+    parent: Option<InscriptionId>,
+    delegate: Option<InscriptionId>,
+}
+
+/// Encodes a tag value as minimal little-endian bytes, ie. with trailing
+/// zero bytes stripped, the same way the `ord` reference client encodes
+/// integer tag values such as the pointer.
+fn encode_tag_u64(value: u64) -> Vec<u8> {
+    let bytes = value.to_le_bytes();
+    let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |pos| pos + 1);
+    bytes[..end].to_vec()
+}
+
+/// Encodes an [`InscriptionId`] as its 32-byte txid followed by the index,
+/// the same way the `ord` reference client encodes the parent/delegate tag
+/// values. The index is minimal little-endian, trimmed of trailing zero
+/// bytes the same way [`encode_tag_u64`] trims the pointer, and omitted
+/// entirely when it is zero.
+fn encode_inscription_id(id: &InscriptionId) -> Vec<u8> {
+    let mut bytes = id.txid.to_byte_array().to_vec();
+    if id.index != 0 {
+        bytes.extend_from_slice(&encode_tag_u64(id.index as u64));
     }
-    pub fn spend_info(&self) -> &TaprootSpendInfo {
-        &self.envelope.spend_info
+    bytes
+}
+
+/// Decodes a tag value produced by [`encode_tag_u64`] back into a `u64`.
+fn decode_tag_u64(bytes: &[u8]) -> Result<u64> {
+    if bytes.len() > 8 {
+        return Err(Error::Todo);
     }
-    pub fn recipient(&self) -> &Recipient<TaprootScript> {
-        &self.recipient
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Decodes an [`InscriptionId`] produced by [`encode_inscription_id`].
+fn decode_inscription_id(bytes: &[u8]) -> Result<InscriptionId> {
+    if bytes.len() < 32 || bytes.len() > 36 {
+        return Err(Error::Todo);
     }
+
+    let txid = Txid::from_slice(&bytes[..32]).map_err(|_| Error::Todo)?;
+
+    let mut index_buf = [0u8; 4];
+    index_buf[..bytes.len() - 32].copy_from_slice(&bytes[32..]);
+    let index = u32::from_le_bytes(index_buf);
+
+    Ok(InscriptionId { txid, index })
+}
+
+/// Computes and verifies the single-leaf Taproot control block for
+/// `envelope`'s script: `(0xc0 | parity_byte) || internal_key_x_only ||
+/// merkle_path`. Verification recomputes the leaf hash and checks it against
+/// the tweaked output key, so a returned control block is guaranteed to
+/// satisfy the script-path spend verifier.
+fn control_block_for(envelope: &TaprootProgram) -> Result<ControlBlock> {
+    let control_block = envelope
+        .spend_info
+        .control_block(&(envelope.script.clone(), LeafVersion::TapScript))
+        .ok_or(Error::Todo)?;
+
+    let secp = secp256k1::Secp256k1::verification_only();
+    let output_key = envelope.spend_info.output_key();
+    if !control_block.verify_taproot_commitment(&secp, output_key.to_inner(), &envelope.script) {
+        return Err(Error::Todo);
+    }
+
+    Ok(control_block)
+}
+
+/// Assembles the reveal-stage, script-path spend witness for `envelope`:
+/// `[<signature>, <tapscript>, <control_block>]`. `envelope`'s leaf script
+/// spends via `<internal_key> OP_CHECKSIG`, so `signature` is its sole stack
+/// input.
+fn reveal_witness_for(envelope: &TaprootProgram, signature: &[u8]) -> Result<Witness> {
+    let control_block = control_block_for(envelope)?;
+
+    let mut witness = Witness::new();
+    witness.push(signature);
+    witness.push(envelope.script.as_script());
+    witness.push(control_block.serialize());
+
+    Ok(witness)
+}
+
+/// Pushes an envelope tag field onto `builder`: the tag byte followed by its
+/// value, each as its own `push_slice`.
+fn push_tag(builder: Builder, tag: u8, value: &[u8]) -> Result<Builder> {
+    let mut tag_buf = PushBytesBuf::new();
+    tag_buf.extend_from_slice(&[tag]).map_err(|_| Error::Todo)?;
+
+    let mut value_buf = PushBytesBuf::new();
+    value_buf.extend_from_slice(value).map_err(|_| Error::Todo)?;
+
+    Ok(builder.push_slice(tag_buf).push_slice(value_buf))
 }
 
 /// Creates an [Ordinals Inscription](https://docs.ordinals.com/inscriptions.html).
@@ -57,7 +394,18 @@ impl OrdinalsInscription {
 /// could also be the same entity. Stage one, the `internal_key` is the
 /// recipient. Stage two, the `internal_key` is the claimer of the transaction
 /// (where the Inscription script is available in the Witness).
-fn create_envelope(mime: &[u8], data: &[u8], internal_key: PublicKey) -> Result<TaprootProgram> {
+///
+/// The envelope itself is never executed (`OP_IF` always takes the false
+/// branch), so the leaf script is prefixed with `<internal_key> OP_CHECKSIG`:
+/// spending the reveal output via this script path requires a signature from
+/// `internal_key`, which [`reveal_witness`](OrdinalsInscription::reveal_witness)
+/// takes as its stack input.
+fn create_envelope(
+    mime: &[u8],
+    data: &[u8],
+    internal_key: PublicKey,
+    tags: &EnvelopeTags,
+) -> Result<TaprootProgram> {
     use bitcoin::opcodes::all::*;
     use bitcoin::opcodes::*;
 
@@ -65,12 +413,14 @@ fn create_envelope(mime: &[u8], data: &[u8], internal_key: PublicKey) -> Result<
     let mut mime_buf = PushBytesBuf::new();
     mime_buf.extend_from_slice(mime).map_err(|_| Error::Todo)?;
 
-    // Create data buffer.
-    let mut data_buf = PushBytesBuf::new();
-    data_buf.extend_from_slice(data).map_err(|_| Error::Todo)?;
-
-    // Create an Ordinals Inscription.
+    // Create an Ordinals Inscription. The envelope itself (`OP_FALSE OP_IF
+    // ... OP_ENDIF`) is never executed, so it needs a real spending condition
+    // in front of it, the same way `ord` reveal scripts do: a signature check
+    // against the recipient's key.
+    let internal_key_xonly = XOnlyPublicKey::from(internal_key.inner);
     let builder = ScriptBuf::builder()
+        .push_x_only_key(&internal_key_xonly)
+        .push_opcode(OP_CHECKSIG)
         .push_opcode(OP_FALSE)
         .push_opcode(OP_IF)
         .push_slice(b"ord")
@@ -88,23 +438,67 @@ fn create_envelope(mime: &[u8], data: &[u8], internal_key: PublicKey) -> Result<
     // <OP_PUSHDATA[1|2|4]><DATA.len><DATA>
     //
     // However, when dealing with the MIME type of an Ordinal Inscription, the
-    // requirements differ. The OP_PUSHDATA prefix is always needed, regardless
-    // of whether the number of bytes pushed to the script is below 76.
-    let builder = if data.len() < 76 {
-        builder.push_opcode(OP_PUSHBYTES_1)
-    } else {
-        builder
-    };
+    // requirements differ. The content-type tag byte itself must be its own
+    // one-byte push, regardless of the MIME or body length, so that the
+    // subsequent `push_slice(mime_buf)` below starts at its own self-contained
+    // encoding. This is always needed, not just when the data happens to be
+    // 75 bytes or less.
+    let builder = builder.push_opcode(OP_PUSHBYTES_1);
 
-    let script = builder
+    let mut builder = builder
         // MIME type identifying the data
-        .push_slice(mime_buf.as_push_bytes())
-        // Separator.
-        .push_opcode(OP_PUSHBYTES_0)
-        // The data itself.
-        .push_slice(data_buf)
-        .push_opcode(OP_ENDIF)
-        .into_script();
+        .push_slice(mime_buf.as_push_bytes());
+
+    // Optional envelope tags, emitted in canonical ascending order between
+    // the content-type and the body separator.
+    if let Some(pointer) = tags.pointer {
+        builder = push_tag(builder, TAG_POINTER, &encode_tag_u64(pointer))?;
+    }
+    if let Some(parent) = &tags.parent {
+        builder = push_tag(builder, TAG_PARENT, &encode_inscription_id(parent))?;
+    }
+    if let Some(metadata) = &tags.metadata {
+        // Metadata is CBOR that may exceed a single script element; like the
+        // body, it is split into chunks, each one re-emitting the tag. Empty
+        // metadata is still a deliberate choice (distinct from no `.metadata()`
+        // call at all), so `[].chunks()` yielding nothing is special-cased to
+        // still emit a single empty-value tag.
+        if metadata.is_empty() {
+            builder = push_tag(builder, TAG_METADATA, &[])?;
+        } else {
+            for chunk in metadata.chunks(MAX_SCRIPT_ELEMENT_SIZE) {
+                builder = push_tag(builder, TAG_METADATA, chunk)?;
+            }
+        }
+    }
+    if let Some(content_encoding) = &tags.content_encoding {
+        builder = push_tag(builder, TAG_CONTENT_ENCODING, content_encoding)?;
+    }
+    if let Some(delegate) = &tags.delegate {
+        builder = push_tag(builder, TAG_DELEGATE, &encode_inscription_id(delegate))?;
+    }
+
+    // Separator.
+    let mut builder = builder.push_opcode(OP_PUSHBYTES_0);
+
+    // The data itself. A single push is enough as long as it fits within a
+    // script element, otherwise the body must be split into consecutive
+    // `MAX_SCRIPT_ELEMENT_SIZE`-sized chunks, each pushed on its own. There
+    // are no tags or separators between chunks; the interpreter concatenates
+    // them back together when the envelope is parsed at reveal.
+    if data.len() <= MAX_SCRIPT_ELEMENT_SIZE {
+        let mut data_buf = PushBytesBuf::new();
+        data_buf.extend_from_slice(data).map_err(|_| Error::Todo)?;
+        builder = builder.push_slice(data_buf);
+    } else {
+        for chunk in data.chunks(MAX_SCRIPT_ELEMENT_SIZE) {
+            let mut chunk_buf = PushBytesBuf::new();
+            chunk_buf.extend_from_slice(chunk).map_err(|_| Error::Todo)?;
+            builder = builder.push_slice(chunk_buf);
+        }
+    }
+
+    let script = builder.push_opcode(OP_ENDIF).into_script();
 
     // Generate the necessary spending information. As mentioned in the
     // documentation of this function at the top, this serves two purposes;
@@ -121,3 +515,511 @@ fn create_envelope(mime: &[u8], data: &[u8], internal_key: PublicKey) -> Result<
 
     Ok(TaprootProgram { script, spend_info })
 }
+
+/// The BIP341 "nothing up my sleeve" point, used as a Taproot internal key
+/// when a program must not support a key-path spend. Nobody knows its
+/// discrete log, so a key-path spend against it is infeasible; only the
+/// tapscript leaf(s) committed to in the Merkle root are spendable.
+const NUMS_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Returns the [`NUMS_INTERNAL_KEY`] parsed as an [`XOnlyPublicKey`].
+fn nums_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&NUMS_INTERNAL_KEY)
+        .expect("NUMS_INTERNAL_KEY must always be a valid x-only public key")
+}
+
+/// The maximum payload size supported by [`CommitRevealPeg`]. Unlike an
+/// Inscription body, the sBTC commit/reveal scheme's payload is a compact
+/// peg-in memo rather than general-purpose data, so it is capped at 80
+/// bytes, the same limit as an `OP_RETURN` output.
+const COMMIT_REVEAL_PEG_MAX_PAYLOAD: usize = 80;
+
+/// A commit-reveal peg-in program, as used by the sBTC peg-in scheme: a
+/// single Taproot leaf that commits to an arbitrary (`<=` 80-byte) payload
+/// via an ignored `OP_DROP` push, and otherwise grants a `signer` key an
+/// unconditional spend and a `reclaim` key a spend gated behind a relative
+/// timelock. Use [`CommitRevealPeg::new`] to build one.
+#[derive(Debug, Clone)]
+pub struct CommitRevealPeg {
+    program: TaprootProgram,
+    payload: Vec<u8>,
+}
+
+impl CommitRevealPeg {
+    /// Builds a new commit-reveal peg-in program ("commit stage").
+    ///
+    /// The resulting tapscript commits to `payload` (recoverable from a
+    /// revealed witness via [`CommitRevealPeg::extract_payload`]), then lets
+    /// `signer` spend unconditionally and `reclaim` spend once `timeout`
+    /// relative blocks have passed, so the depositor can recover the funds
+    /// if the signer never reveals.
+    pub fn new(
+        payload: &[u8],
+        signer: Recipient<PublicKey>,
+        reclaim: Recipient<PublicKey>,
+        timeout: u16,
+    ) -> Result<CommitRevealPeg> {
+        let program = create_commit_reveal_peg(
+            payload,
+            signer.public_key(),
+            reclaim.public_key(),
+            timeout,
+        )?;
+
+        Ok(CommitRevealPeg {
+            program,
+            payload: payload.to_vec(),
+        })
+    }
+    pub fn taproot_program(&self) -> &Script {
+        self.program.script.as_script()
+    }
+    pub fn spend_info(&self) -> &TaprootSpendInfo {
+        &self.program.spend_info
+    }
+    /// Returns the payload this program committed to.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+    /// Extracts the committed payload back out of a revealed tapscript; the
+    /// inverse of the `<payload> OP_DROP` prefix built by [`create_commit_reveal_peg`].
+    pub fn extract_payload(script: &Script) -> Result<Vec<u8>> {
+        use bitcoin::opcodes::all::OP_DROP;
+
+        let mut instructions = script.instructions();
+
+        let payload = match instructions.next() {
+            Some(Ok(Instruction::PushBytes(bytes))) => bytes.as_bytes().to_vec(),
+            _ => return Err(Error::Todo),
+        };
+        match instructions.next() {
+            Some(Ok(Instruction::Op(op))) if op == OP_DROP => {},
+            _ => return Err(Error::Todo),
+        }
+
+        Ok(payload)
+    }
+}
+
+/// Builds the commit-reveal peg-in tapscript described by the sBTC
+/// commit/reveal scheme:
+///
+/// ```text
+/// <payload> OP_DROP
+/// OP_IF
+///     <signer_xonly> OP_CHECKSIG
+/// OP_ELSE
+///     <timeout> OP_CSV OP_DROP
+///     <reclaim_xonly> OP_CHECKSIG
+/// OP_ENDIF
+/// ```
+///
+/// Spending within the relative timeout takes the signer's signature plus
+/// an `OP_TRUE` branch selector; spending after takes the reclaimer's
+/// signature, an `OP_FALSE` selector, and a sequence number of at least
+/// `timeout`.
+fn create_commit_reveal_peg(
+    payload: &[u8],
+    signer: PublicKey,
+    reclaim: PublicKey,
+    timeout: u16,
+) -> Result<TaprootProgram> {
+    use bitcoin::opcodes::all::*;
+
+    if payload.len() > COMMIT_REVEAL_PEG_MAX_PAYLOAD {
+        return Err(Error::Todo);
+    }
+
+    let mut payload_buf = PushBytesBuf::new();
+    payload_buf.extend_from_slice(payload).map_err(|_| Error::Todo)?;
+
+    let signer_key = XOnlyPublicKey::from(signer.inner);
+    let reclaim_key = XOnlyPublicKey::from(reclaim.inner);
+
+    let script = ScriptBuf::builder()
+        // Commit to the payload; `OP_DROP` discards it so it never affects
+        // execution, but it remains recoverable from the witness.
+        .push_slice(payload_buf)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_IF)
+        .push_x_only_key(&signer_key)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ELSE)
+        .push_int(timeout as i64)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_x_only_key(&reclaim_key)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ENDIF)
+        .into_script();
+
+    // Unlike `create_envelope`, this program must not support a key-path
+    // spend: the whole point of the scheme is that a relayer can only ever
+    // observe the 80-byte payload by watching the *script-path* reveal, so
+    // the internal key is pinned to the BIP341 NUMS point (which has no
+    // known discrete log) rather than the signer's key.
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, script.clone())
+        .expect("CommitRevealPeg spending info must always build")
+        .finalize(&secp256k1::Secp256k1::new(), nums_internal_key())
+        .expect("CommitRevealPeg spending info must always build");
+
+    Ok(TaprootProgram { script, spend_info })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::script::Instruction;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    fn test_pubkey() -> PublicKey {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[0xAB; 32]).unwrap();
+        PublicKey::new(secret_key.public_key(&secp))
+    }
+
+    // Collects the length of every data push found in the envelope's body,
+    // ie. everything pushed after the `OP_PUSHBYTES_0` separator.
+    fn body_chunk_lengths(script: &Script) -> Vec<usize> {
+        // `OP_FALSE` at the start of the envelope is itself an empty push, so
+        // the body separator (tag `0`, also an empty push) is the *second*
+        // empty push seen in the script.
+        let mut empty_pushes_seen = 0;
+        let mut in_body = false;
+        script
+            .instructions()
+            .filter_map(|instr| match instr.unwrap() {
+                Instruction::PushBytes(bytes) if bytes.is_empty() => {
+                    empty_pushes_seen += 1;
+                    in_body = empty_pushes_seen >= 2;
+                    None
+                },
+                Instruction::PushBytes(bytes) if in_body => Some(bytes.len()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_single_chunk() {
+        let data = vec![0xff; 1];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, test_pubkey(), &EnvelopeTags::default())
+                .unwrap();
+
+        assert_eq!(body_chunk_lengths(&envelope.script), vec![data.len()]);
+    }
+
+    #[test]
+    fn envelope_two_chunks() {
+        let data = vec![0xff; MAX_SCRIPT_ELEMENT_SIZE + 1];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, test_pubkey(), &EnvelopeTags::default())
+                .unwrap();
+
+        assert_eq!(
+            body_chunk_lengths(&envelope.script),
+            vec![MAX_SCRIPT_ELEMENT_SIZE, 1]
+        );
+    }
+
+    #[test]
+    fn envelope_three_chunks() {
+        let data = vec![0xff; MAX_SCRIPT_ELEMENT_SIZE * 2 + 123];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, test_pubkey(), &EnvelopeTags::default())
+                .unwrap();
+
+        assert_eq!(
+            body_chunk_lengths(&envelope.script),
+            vec![MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_ELEMENT_SIZE, 123]
+        );
+    }
+
+    #[test]
+    fn decode_roundtrips_plain_envelope() {
+        let mime = b"text/plain;charset=utf-8".to_vec();
+        let data = vec![0xab; MAX_SCRIPT_ELEMENT_SIZE + 10];
+        let envelope =
+            create_envelope(&mime, &data, test_pubkey(), &EnvelopeTags::default()).unwrap();
+
+        let decoded = OrdinalsInscription::from_script(&envelope.script).unwrap();
+
+        assert_eq!(decoded.mime, mime);
+        assert_eq!(decoded.body, data);
+        assert_eq!(decoded.content_encoding, None);
+        assert_eq!(decoded.metadata, None);
+        assert_eq!(decoded.pointer, None);
+        assert_eq!(decoded.parent, None);
+        assert_eq!(decoded.delegate, None);
+    }
+
+    #[test]
+    fn decode_roundtrips_tags() {
+        let mime = b"text/plain;charset=utf-8".to_vec();
+        let data = vec![0x01, 0x02, 0x03];
+        let tags = EnvelopeTags {
+            content_encoding: Some(b"br".to_vec()),
+            metadata: Some(vec![0x42; MAX_SCRIPT_ELEMENT_SIZE + 5]),
+            pointer: Some(128),
+            parent: None,
+            delegate: None,
+        };
+        let envelope = create_envelope(&mime, &data, test_pubkey(), &tags).unwrap();
+
+        let decoded = OrdinalsInscription::from_script(&envelope.script).unwrap();
+
+        assert_eq!(decoded.mime, mime);
+        assert_eq!(decoded.body, data);
+        assert_eq!(decoded.content_encoding, tags.content_encoding);
+        assert_eq!(decoded.metadata, tags.metadata);
+        assert_eq!(decoded.pointer, tags.pointer);
+    }
+
+    #[test]
+    fn decode_roundtrips_parent_and_delegate() {
+        let mime = b"text/plain".to_vec();
+        let data = vec![0x01, 0x02, 0x03];
+        let tags = EnvelopeTags {
+            content_encoding: None,
+            metadata: None,
+            pointer: None,
+            parent: Some(InscriptionId {
+                txid: Txid::from_byte_array([0x11; 32]),
+                index: 256,
+            }),
+            delegate: Some(InscriptionId {
+                txid: Txid::from_byte_array([0x22; 32]),
+                index: 0,
+            }),
+        };
+        let envelope = create_envelope(&mime, &data, test_pubkey(), &tags).unwrap();
+
+        let decoded = OrdinalsInscription::from_script(&envelope.script).unwrap();
+
+        assert_eq!(
+            decoded.parent,
+            Some(InscriptionId {
+                txid: Txid::from_byte_array([0x11; 32]),
+                index: 256,
+            })
+        );
+        assert_eq!(
+            decoded.delegate,
+            Some(InscriptionId {
+                txid: Txid::from_byte_array([0x22; 32]),
+                index: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn encode_inscription_id_trims_index_bytes() {
+        let id = InscriptionId {
+            txid: Txid::from_byte_array([0x11; 32]),
+            index: 256,
+        };
+
+        let encoded = encode_inscription_id(&id);
+
+        assert_eq!(&encoded[32..], &[0x00, 0x01]);
+    }
+
+    #[test]
+    fn decode_roundtrips_empty_metadata() {
+        let mime = b"text/plain".to_vec();
+        let data = vec![0x01];
+        let tags = EnvelopeTags {
+            content_encoding: None,
+            metadata: Some(vec![]),
+            pointer: None,
+            parent: None,
+            delegate: None,
+        };
+        let envelope = create_envelope(&mime, &data, test_pubkey(), &tags).unwrap();
+
+        let decoded = OrdinalsInscription::from_script(&envelope.script).unwrap();
+
+        assert_eq!(decoded.metadata, Some(vec![]));
+    }
+
+    #[test]
+    fn builder_chain_roundtrips_through_public_api() {
+        let mime = b"text/plain;charset=utf-8".to_vec();
+        let data = vec![0x01, 0x02, 0x03];
+
+        let inscription = OrdinalsInscription::builder(&mime, &data, Recipient::from(test_pubkey()))
+            .content_encoding(b"br")
+            .metadata(&[0xaa, 0xbb])
+            .pointer(128)
+            .parent(InscriptionId {
+                txid: Txid::from_byte_array([0x11; 32]),
+                index: 1,
+            })
+            .delegate(InscriptionId {
+                txid: Txid::from_byte_array([0x22; 32]),
+                index: 0,
+            })
+            .build()
+            .unwrap();
+
+        let decoded = OrdinalsInscription::from_script(inscription.taproot_program()).unwrap();
+
+        assert_eq!(decoded.mime, mime);
+        assert_eq!(decoded.body, data);
+        assert_eq!(decoded.content_encoding, Some(b"br".to_vec()));
+        assert_eq!(decoded.metadata, Some(vec![0xaa, 0xbb]));
+        assert_eq!(decoded.pointer, Some(128));
+        assert_eq!(
+            decoded.parent,
+            Some(InscriptionId {
+                txid: Txid::from_byte_array([0x11; 32]),
+                index: 1,
+            })
+        );
+        assert_eq!(
+            decoded.delegate,
+            Some(InscriptionId {
+                txid: Txid::from_byte_array([0x22; 32]),
+                index: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_ignores_trailing_script() {
+        use bitcoin::opcodes::all::OP_CHECKSIG;
+
+        let mime = b"text/plain".to_vec();
+        let data = vec![0xaa; 4];
+        let envelope =
+            create_envelope(&mime, &data, test_pubkey(), &EnvelopeTags::default()).unwrap();
+
+        let script = envelope
+            .script
+            .into_bytes()
+            .into_iter()
+            .chain(std::iter::once(OP_CHECKSIG.to_u8()))
+            .collect::<Vec<u8>>();
+        let script = ScriptBuf::from_bytes(script);
+
+        let decoded = OrdinalsInscription::from_script(&script).unwrap();
+        assert_eq!(decoded.mime, mime);
+        assert_eq!(decoded.body, data);
+    }
+
+    #[test]
+    fn reveal_control_block_verifies_against_output_key() {
+        let data = vec![0xff; 3];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, test_pubkey(), &EnvelopeTags::default())
+                .unwrap();
+
+        let control_block = control_block_for(&envelope).unwrap();
+        let secp = Secp256k1::verification_only();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            envelope.spend_info.output_key().to_inner(),
+            &envelope.script,
+        ));
+    }
+
+    #[test]
+    fn reveal_witness_contains_signature_tapscript_and_control_block() {
+        let data = vec![0xff; 3];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, test_pubkey(), &EnvelopeTags::default())
+                .unwrap();
+
+        let signature = vec![0x55; 64];
+        let control_block = control_block_for(&envelope).unwrap();
+        let witness = reveal_witness_for(&envelope, &signature).unwrap();
+
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness.iter().next().unwrap(), signature.as_slice());
+        assert_eq!(witness.iter().nth(1).unwrap(), envelope.script.as_bytes());
+        assert_eq!(witness.iter().nth(2).unwrap(), control_block.serialize());
+    }
+
+    #[test]
+    fn envelope_leaf_script_checks_recipient_signature() {
+        // The envelope body (`OP_FALSE OP_IF ... OP_ENDIF`) never executes,
+        // so the leaf needs a real spending condition in front of it;
+        // without one, any reveal witness would leave an empty final stack
+        // and be rejected by `SCRIPT_ERR_EVAL_FALSE`.
+        let recipient = test_pubkey();
+        let data = vec![0xff; 3];
+        let envelope =
+            create_envelope(b"text/plain;charset=utf-8", &data, recipient, &EnvelopeTags::default())
+                .unwrap();
+
+        let mut instructions = envelope.script.instructions();
+        let key_push = match instructions.next() {
+            Some(Ok(Instruction::PushBytes(bytes))) => bytes.as_bytes().to_vec(),
+            other => panic!("expected a pubkey push, got {other:?}"),
+        };
+        assert_eq!(key_push, XOnlyPublicKey::from(recipient.inner).serialize());
+        match instructions.next() {
+            Some(Ok(Instruction::Op(op))) if op == bitcoin::opcodes::all::OP_CHECKSIG => {},
+            other => panic!("expected OP_CHECKSIG, got {other:?}"),
+        }
+    }
+
+    fn test_pubkey2() -> PublicKey {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[0xCD; 32]).unwrap();
+        PublicKey::new(secret_key.public_key(&secp))
+    }
+
+    #[test]
+    fn commit_reveal_peg_rejects_oversized_payload() {
+        let payload = vec![0x11; COMMIT_REVEAL_PEG_MAX_PAYLOAD + 1];
+        let result = create_commit_reveal_peg(&payload, test_pubkey(), test_pubkey2(), 144);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn commit_reveal_peg_extract_payload_roundtrips() {
+        let payload = vec![0x42; COMMIT_REVEAL_PEG_MAX_PAYLOAD];
+        let program = create_commit_reveal_peg(&payload, test_pubkey(), test_pubkey2(), 144).unwrap();
+
+        let extracted = CommitRevealPeg::extract_payload(&program.script).unwrap();
+        assert_eq!(extracted, payload);
+    }
+
+    #[test]
+    fn commit_reveal_peg_control_block_verifies_against_output_key() {
+        let payload = vec![0x42; COMMIT_REVEAL_PEG_MAX_PAYLOAD];
+        let program = create_commit_reveal_peg(&payload, test_pubkey(), test_pubkey2(), 144).unwrap();
+
+        let control_block = program
+            .spend_info
+            .control_block(&(program.script.clone(), LeafVersion::TapScript))
+            .unwrap();
+        let secp = Secp256k1::verification_only();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            program.spend_info.output_key().to_inner(),
+            &program.script,
+        ));
+    }
+
+    #[test]
+    fn commit_reveal_peg_has_no_key_path_spend() {
+        // The internal key must be the NUMS point, not the signer's (or
+        // anyone's) key, so there is no key-path spend that could bypass the
+        // tapscript and let the payload go unrevealed.
+        let payload = vec![0x42; COMMIT_REVEAL_PEG_MAX_PAYLOAD];
+        let program = create_commit_reveal_peg(&payload, test_pubkey(), test_pubkey2(), 144).unwrap();
+
+        assert_eq!(program.spend_info.internal_key(), nums_internal_key());
+        assert_ne!(
+            program.spend_info.internal_key(),
+            XOnlyPublicKey::from(test_pubkey().inner)
+        );
+    }
+}